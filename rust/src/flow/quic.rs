@@ -0,0 +1,83 @@
+//! QUIC transport backend for the flow protocol, built on quinn/rustls.
+//!
+//! `QuicBiStream` adapts one bidirectional stream's send/receive halves
+//! into a single `AsyncRead + AsyncWrite` type, used for the control stream
+//! that carries the connection-level `ConnectPacket` handshake (same as TCP
+//! and Unix, via `connection::new`). Individual flow requests instead get
+//! their own bidirectional stream via `accept_halves`, kept as raw
+//! send/recv halves so a request's reply can be written straight back to
+//! its own stream without going through the control stream.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::flow::Result;
+
+pub struct QuicBiStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl QuicBiStream {
+    fn new(send: quinn::SendStream, recv: quinn::RecvStream) -> Self {
+        QuicBiStream { send, recv }
+    }
+}
+
+impl AsyncRead for QuicBiStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicBiStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// Builds a client-side QUIC endpoint and opens a single bidirectional
+/// stream to `addr`, authenticated against `server_name` per the peer's
+/// certificate.
+pub async fn connect(addr: std::net::SocketAddr, server_name: &str) -> Result<QuicBiStream> {
+    let client_config = quinn::ClientConfig::with_native_roots();
+    let mut endpoint = quinn::Endpoint::client("0.0.0.0:0".parse().unwrap())?;
+    endpoint.set_default_client_config(client_config);
+    let connection = endpoint.connect(addr, server_name)?.await?;
+    let (send, recv) = connection.open_bi().await?;
+    Ok(QuicBiStream::new(send, recv))
+}
+
+/// Accepts the next inbound bidirectional stream on an already-established
+/// QUIC connection, for use on the listener side after `Endpoint::accept`.
+pub async fn accept(connection: &quinn::Connection) -> Result<QuicBiStream> {
+    let (send, recv) = connection.accept_bi().await?;
+    Ok(QuicBiStream::new(send, recv))
+}
+
+/// Accepts the next inbound bidirectional stream as raw halves, for a
+/// single flow request multiplexed onto its own stream within an
+/// already-negotiated QUIC connection.
+pub async fn accept_halves(
+    connection: &quinn::Connection,
+) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+    Ok(connection.accept_bi().await?)
+}
@@ -1,31 +1,97 @@
 use crate::flow::{
-    connection, file_identifier::FileIdentifierNames, Error, Flow, FlowFuture, FlowMessage, Peer,
-    Result,
+    connection, file_identifier::FileIdentifierNames,
+    rate_limiter::{RateLimit, RateLimiter},
+    Address, Error, Flow, FlowFuture, FlowMessage, Peer, Result,
 };
 use crate::services::RequestRouter;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 use tower::Service;
 
 use std::net::SocketAddr;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::task::{Context, Poll};
 
 const MAX_CONNECTIONS: usize = 250;
 const MAX_REQUESTS: usize = MAX_CONNECTIONS * 2;
 
+/// Inclusive range of flow protocol versions this process can speak to.  A
+/// peer whose `ConnectPacket` advertises a version outside this range gets
+/// the incompatible-version handshake instead of a spawned `receiver`/`sender`.
+const COMPATIBLE_PROTOCOL_VERSION_MIN: u64 = 0x0FDB00B071010000;
+const COMPATIBLE_PROTOCOL_VERSION_MAX: u64 = 0x0FDB00B071010000;
+
+fn is_compatible_protocol_version(protocol_version: u64) -> bool {
+    (COMPATIBLE_PROTOCOL_VERSION_MIN..=COMPATIBLE_PROTOCOL_VERSION_MAX)
+        .contains(&protocol_version)
+}
+
+/// Returned by `ConnectionHandler::new_listener` (and friends). `shutdown()`
+/// signals the accept loop to stop and waits for in-flight connections to
+/// drain.
+pub struct ShutdownHandle {
+    shutdown_tx: watch::Sender<bool>,
+    limit_connections: Arc<Semaphore>,
+    max_connections: usize,
+}
+
+impl ShutdownHandle {
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown_tx.send(true);
+        self.limit_connections
+            .acquire_many_owned(self.max_connections as u32)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Waits for SIGINT or SIGTERM and broadcasts a shutdown signal.
+async fn watch_signals(shutdown_tx: watch::Sender<bool>) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+    let _ = shutdown_tx.send(true);
+    Ok(())
+}
+
 /// Takes FlowMessages from multiple threads and writes them to a ConnectionWriter in a single-threaded way
 async fn sender<C: 'static + AsyncWrite + Unpin + Send>(
     mut response_rx: tokio::sync::mpsc::UnboundedReceiver<FlowMessage>,
     mut writer: connection::ConnectionWriter<C>,
+    mut rate_limiter: Option<RateLimiter>,
+    mut shutdown_rx: watch::Receiver<bool>,
 ) -> Result<()> {
-    while let Some(message) = response_rx.recv().await {
+    loop {
+        let message = tokio::select! {
+            message = response_rx.recv() => message,
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    None
+                } else {
+                    continue;
+                }
+            }
+        };
+        let message = match message {
+            Some(message) => message,
+            None => break,
+        };
+        if let Some(rate_limiter) = &mut rate_limiter {
+            rate_limiter.consume(message.frame.len() as f64).await;
+        }
         writer.write_frame(message.frame).await?;
         loop {
             match response_rx.try_recv() {
                 Ok(message) => {
+                    if let Some(rate_limiter) = &mut rate_limiter {
+                        rate_limiter.consume(message.frame.len() as f64).await;
+                    }
                     writer.write_frame(message.frame).await.unwrap();
                 }
                 Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
@@ -36,14 +102,20 @@ async fn sender<C: 'static + AsyncWrite + Unpin + Send>(
             }
         }
     }
+    // Drain and flush whatever was already queued before giving up the connection.
+    while let Ok(message) = response_rx.try_recv() {
+        writer.write_frame(message.frame).await?;
+    }
+    writer.flush().await?;
     Ok(())
 }
 
 /// Takes FlowMessages from a single-threaded connection reader, and runs them in parallel by spawning concurrent tasks.
 async fn receiver<C>(
-    peer: SocketAddr,
+    peer: Address,
     svc: Arc<RequestRouter>,
     mut reader: connection::ConnectionReader<C>,
+    mut shutdown_rx: watch::Receiver<bool>,
 ) -> Result<()>
 where
     C: 'static + AsyncRead + Unpin + Send,
@@ -52,11 +124,25 @@ where
     let svc_clone = svc.clone();
     let mut limit_svc =
         tower::limit::concurrency::ConcurrencyLimit::new(svc_clone.deref(), MAX_REQUESTS);
-    while let Some(frame) = reader.read_frame().await? {
+    loop {
+        let frame = tokio::select! {
+            frame = reader.read_frame() => frame?,
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    None
+                } else {
+                    continue;
+                }
+            }
+        };
+        let frame = match frame {
+            Some(frame) => frame,
+            None => break,
+        };
         let request = FlowMessage::new(
             Flow {
                 dst: Peer::Local(None),
-                src: Peer::Remote(peer),
+                src: Peer::Remote(peer.clone()),
             },
             frame,
         )?;
@@ -82,16 +168,46 @@ where
     Ok(())
 }
 
+/// Reads the single flow request carried by a per-request QUIC stream,
+/// routes it through `request_router`, and writes any reply back on that
+/// same stream rather than through the connection's shared sender.
+async fn quic_request_stream(
+    peer: Address,
+    request_router: Arc<RequestRouter>,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+) -> Result<()> {
+    let mut reader = connection::ConnectionReader::new(recv);
+    let frame = match reader.read_frame().await? {
+        Some(frame) => frame,
+        None => return Ok(()),
+    };
+    let request = FlowMessage::new(
+        Flow {
+            dst: Peer::Local(None),
+            src: Peer::Remote(peer),
+        },
+        frame,
+    )?;
+    if let Some(response) = request_router.deref().call(request).await? {
+        let mut writer = connection::ConnectionWriter::new(send);
+        writer.write_frame(response.frame).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
 fn spawn_receiver<C>(
-    peer: SocketAddr,
+    peer: Address,
     request_router: Arc<RequestRouter>,
     reader: connection::ConnectionReader<C>,
     permit: OwnedSemaphorePermit,
+    shutdown_rx: watch::Receiver<bool>,
 ) where
     C: 'static + AsyncRead + Unpin + Send,
 {
     tokio::spawn(async move {
-        match receiver(peer, request_router, reader).await {
+        match receiver(peer, request_router, reader, shutdown_rx).await {
             Ok(_) => {
                 println!("clean shutdown!");
             }
@@ -106,22 +222,25 @@ fn spawn_receiver<C>(
 fn spawn_sender<C>(
     response_rx: mpsc::UnboundedReceiver<FlowMessage>,
     writer: connection::ConnectionWriter<C>,
+    rate_limit: Option<RateLimit>,
+    shutdown_rx: watch::Receiver<bool>,
 ) where
     C: 'static + AsyncWrite + Unpin + Send,
 {
     tokio::spawn(async move {
-        match sender(response_rx, writer).await {
+        let rate_limiter = rate_limit.map(RateLimit::into_limiter);
+        match sender(response_rx, writer, rate_limiter, shutdown_rx).await {
             Ok(_) => {}
             Err(e) => {
                 println!("Unexpected error from sender! {:?}", e);
             }
         }
-        // TODO: Connection teardown logic?
     });
 }
 
 pub struct ConnectionHandler {
-    pub peer: SocketAddr,
+    pub peer: Address,
+    pub protocol_version: u64,
     pub fit: FileIdentifierNames,
     pub response_tx: mpsc::UnboundedSender<FlowMessage>,
     pub request_router: Arc<RequestRouter>,
@@ -131,47 +250,243 @@ impl std::fmt::Debug for ConnectionHandler {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
         f.debug_struct("ConnectionHandler")
             .field("peer", &self.peer)
+            .field("protocol_version", &format_args!("{:#x}", self.protocol_version))
             .finish()
     }
 }
 
 impl ConnectionHandler {
-    async fn new(
-        socket: (TcpStream, SocketAddr),
+    async fn new<C>(
+        stream: C,
+        peer: Address,
         permit: OwnedSemaphorePermit,
         request_router: Arc<RequestRouter>,
-    ) -> Result<Arc<Self>> {
-        let (stream, peer) = socket;
+        rate_limit: Option<RateLimit>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Arc<Self>>
+    where
+        C: 'static + AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let (reader, mut writer, connect_packet) = connection::new(stream).await?;
+        println!("{} {:x?}", peer, connect_packet);
+
+        let protocol_version = connect_packet.protocol_version;
+        if !is_compatible_protocol_version(protocol_version) {
+            writer.write_incompatible_protocol_version().await?;
+            return Err(format!(
+                "peer {} speaks incompatible flow protocol version {:#x} (supported: {:#x}-{:#x})",
+                peer, protocol_version, COMPATIBLE_PROTOCOL_VERSION_MIN, COMPATIBLE_PROTOCOL_VERSION_MAX
+            )
+            .into());
+        }
+
         // TODO: Backpressure?
         let (response_tx, response_rx) = tokio::sync::mpsc::unbounded_channel::<FlowMessage>();
         let connection_handler = ConnectionHandler {
-            peer,
+            peer: peer.clone(),
+            protocol_version,
             fit: FileIdentifierNames::new().unwrap(),
             response_tx,
             request_router,
         };
-        let (reader, writer, connect_packet) = connection::new(stream).await?;
-        // TODO: Check protocol compatibility, create object w/ enough info to allow request routing
-        println!("{} {:x?}", peer, connect_packet);
         let connection_handler = Arc::new(connection_handler);
-        spawn_sender(response_rx, writer);
+        spawn_sender(response_rx, writer, rate_limit, shutdown_rx.clone());
         spawn_receiver(
-            connection_handler.peer,
+            connection_handler.peer.clone(),
             connection_handler.request_router.clone(),
             reader,
             permit,
+            shutdown_rx,
         );
         Ok(connection_handler)
     }
 
+    /// `shutdown_rx` should be a clone of the same receiver handed to every
+    /// other outgoing/listening connection in this process (e.g. the one a
+    /// `ShutdownHandle` was built from), so this connection actually stops
+    /// on shutdown instead of spinning on a receiver whose sender was
+    /// immediately dropped.
     pub async fn new_outgoing_connection(
         saddr: SocketAddr,
         request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        shutdown_rx: watch::Receiver<bool>,
     ) -> Result<Arc<ConnectionHandler>> {
         let conn = TcpStream::connect(saddr).await?;
         let limit_connections = Arc::new(Semaphore::new(1));
         let permit = limit_connections.clone().acquire_owned().await?;
-        ConnectionHandler::new((conn, saddr), permit, request_router).await
+        ConnectionHandler::new(
+            conn,
+            Address::Tcp(saddr),
+            permit,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        )
+        .await
+    }
+
+    /// See `new_outgoing_connection` for the `shutdown_rx` contract.
+    pub async fn new_outgoing_unix_connection(
+        path: impl AsRef<Path>,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Arc<ConnectionHandler>> {
+        let path = path.as_ref().to_path_buf();
+        let conn = UnixStream::connect(&path).await?;
+        let limit_connections = Arc::new(Semaphore::new(1));
+        let permit = limit_connections.clone().acquire_owned().await?;
+        ConnectionHandler::new(
+            conn,
+            Address::Unix(path),
+            permit,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        )
+        .await
+    }
+
+    /// See `new_outgoing_connection` for the `shutdown_rx` contract.
+    pub async fn new_outgoing_quic_connection(
+        saddr: SocketAddr,
+        server_name: &str,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Arc<ConnectionHandler>> {
+        let stream = crate::flow::quic::connect(saddr, server_name).await?;
+        let limit_connections = Arc::new(Semaphore::new(1));
+        let permit = limit_connections.clone().acquire_owned().await?;
+        ConnectionHandler::new(
+            stream,
+            Address::Tcp(saddr),
+            permit,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        )
+        .await
+    }
+
+    async fn quic_listener(
+        endpoint: quinn::Endpoint,
+        limit_connections: Arc<Semaphore>,
+        tx: mpsc::Sender<Arc<ConnectionHandler>>,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        return Ok(());
+                    }
+                }
+                connecting = endpoint.accept() => {
+                    let connecting = match connecting {
+                        Some(connecting) => connecting,
+                        None => return Ok(()),
+                    };
+                    let connection = connecting.await?;
+                    let addr = connection.remote_address();
+                    tokio::spawn(Self::quic_connection_requests(
+                        connection,
+                        addr,
+                        limit_connections.clone(),
+                        tx.clone(),
+                        request_router.clone(),
+                        rate_limit,
+                        shutdown_rx.clone(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Negotiates the `ConnectPacket` handshake once per QUIC connection,
+    /// on its first accepted bidirectional stream, and charges exactly one
+    /// `limit_connections` permit for the connection's whole lifetime (not
+    /// one per request). Every later stream on this connection carries a
+    /// single flow request, handled by `quic_request_stream` and replied
+    /// to on that same stream, so independent requests still don't
+    /// head-of-line block each other behind one shared byte stream.
+    async fn quic_connection_requests(
+        connection: quinn::Connection,
+        addr: SocketAddr,
+        limit_connections: Arc<Semaphore>,
+        tx: mpsc::Sender<Arc<ConnectionHandler>>,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<()> {
+        let permit = limit_connections.acquire_owned().await?;
+        let control_stream = crate::flow::quic::accept(&connection).await?;
+        let handler = match ConnectionHandler::new(
+            control_stream,
+            Address::Tcp(addr),
+            permit,
+            request_router.clone(),
+            rate_limit,
+            shutdown_rx.clone(),
+        )
+        .await
+        {
+            Ok(handler) => handler,
+            Err(e) => {
+                println!("rejecting quic connection from {}: {:?}", addr, e);
+                return Ok(());
+            }
+        };
+        tx.send(handler.clone()).await?;
+
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        return Ok(());
+                    }
+                }
+                accepted = crate::flow::quic::accept_halves(&connection) => {
+                    let (send, recv) = accepted?;
+                    tokio::spawn(quic_request_stream(
+                        handler.peer.clone(),
+                        request_router.clone(),
+                        send,
+                        recv,
+                    ));
+                }
+            }
+        }
+    }
+
+    pub async fn new_quic_listener(
+        endpoint: quinn::Endpoint,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<(mpsc::Receiver<Arc<ConnectionHandler>>, ShutdownHandle)> {
+        let limit_connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        let (tx, rx) = mpsc::channel(100);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(watch_signals(shutdown_tx.clone()));
+        tokio::spawn(Self::quic_listener(
+            endpoint,
+            limit_connections.clone(),
+            tx,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        ));
+        Ok((
+            rx,
+            ShutdownHandle {
+                shutdown_tx,
+                limit_connections,
+                max_connections: MAX_CONNECTIONS,
+            },
+        ))
     }
 
     async fn listener(
@@ -179,25 +494,144 @@ impl ConnectionHandler {
         limit_connections: Arc<Semaphore>,
         tx: mpsc::Sender<Arc<ConnectionHandler>>,
         request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        mut shutdown_rx: watch::Receiver<bool>,
     ) -> Result<()> {
         loop {
-            let permit = limit_connections.clone().acquire_owned().await?;
-            let socket = bind.accept().await?;
-            tx.send(ConnectionHandler::new(socket, permit, request_router.clone()).await?)
-                .await?; // Send will return error if the Receiver has been close()'ed.
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        return Ok(());
+                    }
+                }
+                accepted = bind.accept() => {
+                    let (stream, addr) = accepted?;
+                    let permit = limit_connections.clone().acquire_owned().await?;
+                    let handler = match ConnectionHandler::new(
+                        stream,
+                        Address::Tcp(addr),
+                        permit,
+                        request_router.clone(),
+                        rate_limit,
+                        shutdown_rx.clone(),
+                    )
+                    .await
+                    {
+                        Ok(handler) => handler,
+                        Err(e) => {
+                            println!("rejecting connection from {}: {:?}", addr, e);
+                            continue;
+                        }
+                    };
+                    tx.send(handler).await?; // Send will return error if the Receiver has been close()'ed.
+                }
+            }
         }
     }
 
+    async fn unix_listener(
+        bind: UnixListener,
+        bind_path: Arc<PathBuf>,
+        limit_connections: Arc<Semaphore>,
+        tx: mpsc::Sender<Arc<ConnectionHandler>>,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.changed() => {
+                    if *shutdown_rx.borrow() {
+                        return Ok(());
+                    }
+                }
+                accepted = bind.accept() => {
+                    let permit = limit_connections.clone().acquire_owned().await?;
+                    // Connecting `UnixStream`s are anonymous on the client side, so the
+                    // bound socket path is the only usable peer identity.
+                    let (stream, _addr) = accepted?;
+                    let handler = match ConnectionHandler::new(
+                        stream,
+                        Address::Unix((*bind_path).clone()),
+                        permit,
+                        request_router.clone(),
+                        rate_limit,
+                        shutdown_rx.clone(),
+                    )
+                    .await
+                    {
+                        Ok(handler) => handler,
+                        Err(e) => {
+                            println!("rejecting connection from {}: {:?}", bind_path.display(), e);
+                            continue;
+                        }
+                    };
+                    tx.send(handler).await?;
+                }
+            }
+        }
+    }
+
+    /// Binds `addr` and spawns the accept loop.  SIGINT/SIGTERM are wired up
+    /// automatically; the returned `ShutdownHandle` can also be used to
+    /// trigger a drain-and-close programmatically.
     pub async fn new_listener(
         addr: &str,
         request_router: Arc<RequestRouter>,
-    ) -> Result<mpsc::Receiver<Arc<ConnectionHandler>>> {
+        rate_limit: Option<RateLimit>,
+    ) -> Result<(mpsc::Receiver<Arc<ConnectionHandler>>, ShutdownHandle)> {
         let bind = TcpListener::bind(addr).await?;
         let limit_connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
         let (tx, rx) = mpsc::channel(100);
-        tokio::spawn(Self::listener(bind, limit_connections, tx, request_router));
-        Ok(rx)
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(watch_signals(shutdown_tx.clone()));
+        tokio::spawn(Self::listener(
+            bind,
+            limit_connections.clone(),
+            tx,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        ));
+        Ok((
+            rx,
+            ShutdownHandle {
+                shutdown_tx,
+                limit_connections,
+                max_connections: MAX_CONNECTIONS,
+            },
+        ))
     }
+
+    pub async fn new_unix_listener(
+        path: impl AsRef<Path>,
+        request_router: Arc<RequestRouter>,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<(mpsc::Receiver<Arc<ConnectionHandler>>, ShutdownHandle)> {
+        let path = path.as_ref().to_path_buf();
+        let bind = UnixListener::bind(&path)?;
+        let limit_connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        let (tx, rx) = mpsc::channel(100);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(Self::unix_listener(
+            bind,
+            Arc::new(path),
+            limit_connections.clone(),
+            tx,
+            request_router,
+            rate_limit,
+            shutdown_rx,
+        ));
+        Ok((
+            rx,
+            ShutdownHandle {
+                shutdown_tx,
+                limit_connections,
+                max_connections: MAX_CONNECTIONS,
+            },
+        ))
+    }
+
     fn handle_req(&self, request: FlowMessage) -> Result<Option<FlowFuture>> {
         request.validate()?;
         self.response_tx.send(request)?;
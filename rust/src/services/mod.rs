@@ -2,6 +2,7 @@ use crate::flow::connection;
 use crate::flow::file_identifier;
 use crate::flow::frame;
 use crate::flow::frame::Frame;
+use crate::flow::rate_limiter::{RateLimit, RateLimiter};
 use crate::flow::uid;
 use crate::flow::Result;
 
@@ -101,13 +102,21 @@ async fn handle_frame(
 fn spawn_sender<C: 'static + AsyncWrite + Unpin + Send>(
     mut response_rx: tokio::sync::mpsc::Receiver<Frame>,
     mut writer: connection::ConnectionWriter<C>,
+    rate_limit: Option<RateLimit>,
 ) {
     tokio::spawn(async move {
+        let mut rate_limiter: Option<RateLimiter> = rate_limit.map(RateLimit::into_limiter);
         while let Some(frame) = response_rx.recv().await {
+            if let Some(rate_limiter) = &mut rate_limiter {
+                rate_limiter.consume(frame.len() as f64).await;
+            }
             writer.write_frame(frame).await.unwrap(); //XXX unwrap!
             loop {
                 match response_rx.try_recv() {
                     Ok(frame) => {
+                        if let Some(rate_limiter) = &mut rate_limiter {
+                            rate_limiter.consume(frame.len() as f64).await;
+                        }
                         writer.write_frame(frame).await.unwrap();
                     }
                     Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
@@ -142,7 +151,7 @@ pub async fn hello_tower() -> Result<()> {
         // set to the process-wide MAX_REQUESTS / 10 so that a few backpressuring receivers
         // can't consume all the request slots for this process.
         let (response_tx, response_rx) = tokio::sync::mpsc::channel::<Frame>(MAX_REQUESTS / 10);
-        spawn_sender(response_rx, writer);
+        spawn_sender(response_rx, writer, None);
 
         tokio::spawn(async move {
             let file_identifier_table = file_identifier::FileIdentifierNames::new()?;
@@ -200,7 +209,7 @@ async fn handle_connection<C: 'static + AsyncRead + AsyncWrite + Unpin + Send>(
     // set to the process-wide MAX_REQUESTS / 10 so that a few backpressuring receivers
     // can't consume all the request slots for this process.
     let (response_tx, response_rx) = tokio::sync::mpsc::channel::<Frame>(MAX_REQUESTS / 10);
-    spawn_sender(response_rx, writer);
+    spawn_sender(response_rx, writer, None);
 
     loop {
         let response_tx = response_tx.clone();
@@ -263,4 +272,41 @@ pub async fn hello() -> Result<()> {
             Ok::<(), crate::flow::Error>(())
         });
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::{test_support, uid::UID};
+
+    fn request_for(frame: frame::Frame) -> Result<FlowRequest> {
+        let file_identifier_table = file_identifier::FileIdentifierNames::new()?;
+        let parsed_file_identifier =
+            file_identifier_table.from_id(frame.peek_file_identifier()?)?;
+        Ok(FlowRequest {
+            frame,
+            parsed_file_identifier,
+        })
+    }
+
+    #[tokio::test]
+    async fn handle_req_replies_to_ping_packet() -> Result<()> {
+        let reply_uid = UID::from_string("0123456789abcdeffedcba9876543210")?;
+        let frame = test_support::ping_packet_frame(reply_uid.clone());
+        let request = request_for(frame)?;
+
+        let response = handle_req(request).await?.expect("ping gets a reply");
+        assert_eq!(response.frame.token, reply_uid);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_req_has_no_reply_for_network_test() -> Result<()> {
+        let frame = test_support::network_test_frame(Vec::new());
+        let request = request_for(frame)?;
+
+        let response = handle_req(request).await?;
+        assert!(response.is_none());
+        Ok(())
+    }
 }
\ No newline at end of file